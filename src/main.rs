@@ -1,35 +1,471 @@
-use bytes::{Buf, BufMut, BytesMut, IntoBuf};
+use bytes::{BufMut, BytesMut};
 use std::fmt;
-use std::net::UdpSocket;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+// Parsing can fail half-way through a malformed packet, so the record
+// readers bubble up a boxed error rather than panicking on the socket.
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// `bytes::Buf` only moves forward, but name compression forces us to seek
+// back to earlier offsets, so responses are parsed out of an indexed slice
+// with its own position cursor.
+struct BytePacketBuffer {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl BytePacketBuffer {
+    fn new(data: &[u8]) -> BytePacketBuffer {
+        BytePacketBuffer {
+            buf: data.to_vec(),
+            pos: 0,
+        }
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    fn step(&mut self, steps: usize) {
+        self.pos += steps;
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        if self.pos >= self.buf.len() {
+            return Err("end of buffer".into());
+        }
+        let res = self.buf[self.pos];
+        self.pos += 1;
+        Ok(res)
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        Ok(((self.read_u8()? as u16) << 8) | (self.read_u8()? as u16))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(((self.read_u16()? as u32) << 16) | (self.read_u16()? as u32))
+    }
+
+    fn get(&self, pos: usize) -> Result<u8> {
+        if pos >= self.buf.len() {
+            return Err("end of buffer".into());
+        }
+        Ok(self.buf[pos])
+    }
+
+    fn get_range(&self, start: usize, len: usize) -> Result<&[u8]> {
+        if start + len > self.buf.len() {
+            return Err("end of buffer".into());
+        }
+        Ok(&self.buf[start..start + len])
+    }
+
+    // Reads a domain name, following compression pointers (top two bits set)
+    // back into the packet. Early DNS parsers could be sent into an infinite
+    // loop by a pointer cycle, so the number of jumps is capped.
+    fn read_qname(&mut self, out: &mut String) -> Result<()> {
+        let mut pos = self.pos();
+
+        let mut jumped = false;
+        let max_jumps = 5;
+        let mut jumps = 0;
+
+        let mut delim = "";
+        loop {
+            if jumps > max_jumps {
+                return Err(format!("limit of {} jumps exceeded", max_jumps).into());
+            }
+
+            let len = self.get(pos)?;
+
+            if (len & 0xC0) == 0xC0 {
+                // The two length bytes form a pointer; remember where to
+                // resume once we are done following it.
+                if !jumped {
+                    self.seek(pos + 2);
+                }
+
+                let b2 = self.get(pos + 1)? as u16;
+                let offset = (((len as u16) ^ 0xC0) << 8) | b2;
+                pos = offset as usize;
+
+                jumped = true;
+                jumps += 1;
+                continue;
+            } else {
+                pos += 1;
+
+                if len == 0 {
+                    break;
+                }
+
+                out.push_str(delim);
+
+                let str_buffer = self.get_range(pos, len as usize)?;
+                out.push_str(&String::from_utf8_lossy(str_buffer).to_lowercase());
+
+                delim = ".";
+                pos += len as usize;
+            }
+        }
+
+        if !jumped {
+            self.seek(pos);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[allow(clippy::upper_case_acronyms)] // DNS type mnemonics are acronyms by spec.
+enum QueryType {
+    Unknown(u16),
+    A,
+    NS,
+    CNAME,
+    SOA,
+    PTR,
+    MX,
+    TXT,
+    AAAA,
+    SRV,
+}
+
+impl QueryType {
+    fn to_num(self) -> u16 {
+        match self {
+            QueryType::Unknown(x) => x,
+            QueryType::A => 1,
+            QueryType::NS => 2,
+            QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::MX => 15,
+            QueryType::TXT => 16,
+            QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+        }
+    }
+
+    fn from_num(num: u16) -> QueryType {
+        match num {
+            1 => QueryType::A,
+            2 => QueryType::NS,
+            5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            15 => QueryType::MX,
+            16 => QueryType::TXT,
+            28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            _ => QueryType::Unknown(num),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)] // DNS record type mnemonics are acronyms by spec.
+#[allow(dead_code)] // Fields are surfaced through the Debug dump, which the lint ignores.
+enum ResourceRecord {
+    Unknown {
+        domain: String,
+        qtype: u16,
+        data_len: u16,
+        ttl: u32,
+    },
+    A {
+        domain: String,
+        addr: Ipv4Addr,
+        ttl: u32,
+    },
+    AAAA {
+        domain: String,
+        addr: Ipv6Addr,
+        ttl: u32,
+    },
+    NS {
+        domain: String,
+        host: String,
+        ttl: u32,
+    },
+    CNAME {
+        domain: String,
+        host: String,
+        ttl: u32,
+    },
+    PTR {
+        domain: String,
+        host: String,
+        ttl: u32,
+    },
+    MX {
+        domain: String,
+        priority: u16,
+        host: String,
+        ttl: u32,
+    },
+    TXT {
+        domain: String,
+        text: String,
+        ttl: u32,
+    },
+    SOA {
+        domain: String,
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        ttl: u32,
+    },
+    SRV {
+        domain: String,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        host: String,
+        ttl: u32,
+    },
+    // EDNS0 pseudo-record (TYPE 41): CLASS is repurposed as the requestor's
+    // UDP payload size and the TTL carries extended-rcode/version/flags.
+    OPT {
+        payload_size: u16,
+        do_bit: bool,
+    },
+}
+
+impl ResourceRecord {
+    fn read(buffer: &mut BytePacketBuffer) -> Result<ResourceRecord> {
+        let mut domain = String::new();
+        buffer.read_qname(&mut domain)?;
+
+        let raw_qtype = buffer.read_u16()?;
+
+        if raw_qtype == 41 {
+            let payload_size = buffer.read_u16()?;
+            let flags = buffer.read_u32()?;
+            let do_bit = (flags & 0x0000_8000) != 0;
+            let data_len = buffer.read_u16()?;
+            buffer.step(data_len as usize);
+            return Ok(ResourceRecord::OPT {
+                payload_size,
+                do_bit,
+            });
+        }
+
+        let qtype = QueryType::from_num(raw_qtype);
+        let _class = buffer.read_u16()?;
+        let ttl = buffer.read_u32()?;
+        let data_len = buffer.read_u16()?;
+
+        match qtype {
+            QueryType::A => {
+                let raw_addr = buffer.read_u32()?;
+                let addr = Ipv4Addr::new(
+                    ((raw_addr >> 24) & 0xFF) as u8,
+                    ((raw_addr >> 16) & 0xFF) as u8,
+                    ((raw_addr >> 8) & 0xFF) as u8,
+                    (raw_addr & 0xFF) as u8,
+                );
+                Ok(ResourceRecord::A { domain, addr, ttl })
+            }
+            QueryType::AAAA => {
+                let addr = Ipv6Addr::new(
+                    buffer.read_u16()?,
+                    buffer.read_u16()?,
+                    buffer.read_u16()?,
+                    buffer.read_u16()?,
+                    buffer.read_u16()?,
+                    buffer.read_u16()?,
+                    buffer.read_u16()?,
+                    buffer.read_u16()?,
+                );
+                Ok(ResourceRecord::AAAA { domain, addr, ttl })
+            }
+            QueryType::NS => {
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+                Ok(ResourceRecord::NS { domain, host, ttl })
+            }
+            QueryType::CNAME => {
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+                Ok(ResourceRecord::CNAME { domain, host, ttl })
+            }
+            QueryType::PTR => {
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+                Ok(ResourceRecord::PTR { domain, host, ttl })
+            }
+            QueryType::MX => {
+                let priority = buffer.read_u16()?;
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+                Ok(ResourceRecord::MX {
+                    domain,
+                    priority,
+                    host,
+                    ttl,
+                })
+            }
+            QueryType::TXT => {
+                // RDATA is one or more length-prefixed character-strings.
+                let end = buffer.pos() + data_len as usize;
+                let mut text = String::new();
+                while buffer.pos() < end {
+                    let len = buffer.read_u8()? as usize;
+                    let bytes = buffer.get_range(buffer.pos(), len)?;
+                    text.push_str(&String::from_utf8_lossy(bytes));
+                    buffer.step(len);
+                }
+                Ok(ResourceRecord::TXT { domain, text, ttl })
+            }
+            QueryType::SOA => {
+                let mut mname = String::new();
+                buffer.read_qname(&mut mname)?;
+                let mut rname = String::new();
+                buffer.read_qname(&mut rname)?;
+                let serial = buffer.read_u32()?;
+                let refresh = buffer.read_u32()?;
+                let retry = buffer.read_u32()?;
+                let expire = buffer.read_u32()?;
+                let minimum = buffer.read_u32()?;
+                Ok(ResourceRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                    ttl,
+                })
+            }
+            QueryType::SRV => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+                Ok(ResourceRecord::SRV {
+                    domain,
+                    priority,
+                    weight,
+                    port,
+                    host,
+                    ttl,
+                })
+            }
+            QueryType::Unknown(_) => {
+                buffer.step(data_len as usize);
+                Ok(ResourceRecord::Unknown {
+                    domain,
+                    qtype: qtype.to_num(),
+                    data_len,
+                    ttl,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum Opcode {
+    #[default]
+    Query,
+    IQuery,
+    Status,
+    Notify,
+    Update,
+    Unknown(u8),
+}
+
+impl Opcode {
+    fn from_num(num: u8) -> Opcode {
+        match num {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            _ => Opcode::Unknown(num),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[allow(clippy::upper_case_acronyms)] // DNS rcode mnemonics are acronyms by spec.
+enum Rcode {
+    #[default]
+    NoError,
+    FormErr,
+    ServFail,
+    NXDomain,
+    NotImp,
+    Refused,
+    Unknown(u8),
+}
+
+impl Rcode {
+    fn from_num(num: u8) -> Rcode {
+        match num {
+            0 => Rcode::NoError,
+            1 => Rcode::FormErr,
+            2 => Rcode::ServFail,
+            3 => Rcode::NXDomain,
+            4 => Rcode::NotImp,
+            5 => Rcode::Refused,
+            _ => Rcode::Unknown(num),
+        }
+    }
+}
 
 #[derive(Default)]
 struct DNSResponse {
     id: u16,
     qr: bool,
-    opcode: u8,
+    opcode: Opcode,
     aa: bool,
     tc: bool,
     rd: bool,
     ra: bool,
     z: u8,
-    rcode: u8,
+    rcode: Rcode,
+    qdcount: u16,
     ancount: u16,
     nscount: u16,
     arcount: u16,
+    answers: Vec<ResourceRecord>,
+    authorities: Vec<ResourceRecord>,
+    resources: Vec<ResourceRecord>,
+    // Populated from an OPT record in the additional section, if present.
+    edns_payload_size: Option<u16>,
+    edns_do: bool,
 }
 
-impl<'a> DNSResponse {
+impl DNSResponse {
     fn new() -> DNSResponse {
         DNSResponse {
             id: rand::random::<u16>(),
             qr: false,
-            opcode: 0,
+            opcode: Opcode::Query,
             aa: false,
             tc: false,
             rd: true,
             ra: false,
             z: 0,
-            rcode: 0,
+            rcode: Rcode::NoError,
             ancount: 0,
             nscount: 0,
             arcount: 0,
@@ -37,25 +473,109 @@ impl<'a> DNSResponse {
         }
     }
 
-    fn from_buffer(&mut self, buf: &mut Buf) {
-        self.id = buf.get_u16_le();
+    // Decodes into an already-constructed response, so it borrows `&mut self`
+    // rather than following the `from_*` associated-constructor convention.
+    #[allow(clippy::wrong_self_convention)]
+    fn from_buffer(&mut self, buf: &mut BytePacketBuffer) -> Result<()> {
+        // The id is written little-endian on the request side, so read it
+        // back the same way to keep the round-trip intact.
+        let lo = buf.read_u8()? as u16;
+        let hi = buf.read_u8()? as u16;
+        self.id = lo | (hi << 8);
 
-        let mut byte = buf.get_u8();
-        self.qr = if byte & 0b10000000 > 0 { true } else { false };
-        self.opcode = byte & 0b01111000;
-        self.aa = if byte & 0b00000100 > 0 { true } else { false };
-        self.tc = if byte & 0b00000010 > 0 { true } else { false };
-        self.rd = if byte & 0b00000001 > 0 { true } else { false };
+        let mut byte = buf.read_u8()?;
+        self.qr = byte & 0b10000000 > 0;
+        self.opcode = Opcode::from_num((byte & 0b01111000) >> 3);
+        self.aa = byte & 0b00000100 > 0;
+        self.tc = byte & 0b00000010 > 0;
+        self.rd = byte & 0b00000001 > 0;
 
-        byte = buf.get_u8();
-        self.ra = if byte & 0b10000000 > 0 { true } else { false };
+        byte = buf.read_u8()?;
+        self.ra = byte & 0b10000000 > 0;
         self.z = byte & 0b01110000;
-        self.rcode = byte & 0b00001111;
+        self.rcode = Rcode::from_num(byte & 0b00001111);
+
+        self.qdcount = buf.read_u16()?;
+        self.ancount = buf.read_u16()?;
+        self.nscount = buf.read_u16()?;
+        self.arcount = buf.read_u16()?;
+
+        // Skip the echoed questions (NAME + QTYPE + QCLASS) so the cursor
+        // lands on the first resource record.
+        for _ in 0..self.qdcount {
+            let mut scratch = String::new();
+            buf.read_qname(&mut scratch)?;
+            buf.read_u16()?; // QTYPE
+            buf.read_u16()?; // QCLASS
+        }
+
+        for _ in 0..self.ancount {
+            self.answers.push(ResourceRecord::read(buf)?);
+        }
+        for _ in 0..self.nscount {
+            self.authorities.push(ResourceRecord::read(buf)?);
+        }
+        for _ in 0..self.arcount {
+            self.resources.push(ResourceRecord::read(buf)?);
+        }
+
+        // Surface the negotiated EDNS0 parameters if the server echoed an OPT.
+        for rr in &self.resources {
+            if let ResourceRecord::OPT {
+                payload_size,
+                do_bit,
+            } = rr
+            {
+                self.edns_payload_size = Some(*payload_size);
+                self.edns_do = *do_bit;
+            }
+        }
+
+        Ok(())
+    }
+
+    // First A record in the answer section, as a dotted-quad string.
+    fn first_a(&self) -> Option<String> {
+        for ans in &self.answers {
+            if let ResourceRecord::A { addr, .. } = ans {
+                return Some(addr.to_string());
+            }
+        }
+        None
+    }
+
+    // Glue address for one of the delegated name servers: an NS host in the
+    // authority section whose A record is present in the additional section.
+    fn matching_glue(&self) -> Option<String> {
+        for auth in &self.authorities {
+            if let ResourceRecord::NS { host, .. } = auth {
+                for res in &self.resources {
+                    if let ResourceRecord::A { domain, addr, .. } = res {
+                        if domain == host {
+                            return Some(addr.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // The first NS hostname in the authority section. Glue is not checked
+    // here; `resolve_iterative` calls this only after `matching_glue` found
+    // no glue for any NS.
+    fn unresolved_ns(&self) -> Option<String> {
+        for auth in &self.authorities {
+            if let ResourceRecord::NS { host, .. } = auth {
+                return Some(host.clone());
+            }
+        }
+        None
     }
 }
 
 #[derive(Default)]
-struct DNSRequest<'a> {
+struct DNSRequest {
     id: u16,
     qr: bool,
     opcode: u8,
@@ -68,11 +588,14 @@ struct DNSRequest<'a> {
     ancount: u16,
     nscount: u16,
     arcount: u16,
-    names: Vec<Vec<&'a str>>,
+    questions: Vec<(Vec<String>, QueryType)>,
+    // When set, an EDNS0 OPT record advertising this UDP payload size is
+    // appended to the additional section.
+    edns_payload_size: Option<u16>,
 }
 
-impl<'a> DNSRequest<'a> {
-    fn new() -> DNSRequest<'a> {
+impl DNSRequest {
+    fn new() -> DNSRequest {
         DNSRequest {
             id: rand::random::<u16>(),
             qr: false,
@@ -118,16 +641,16 @@ impl<'a> DNSRequest<'a> {
         //  RD      - 1 bit
         let mut bt: u8 = self.opcode;
         bt <<= 3;
-        if self.qr == true {
+        if self.qr {
             bt ^= 0b10000000;
         }
-        if self.aa == true {
+        if self.aa {
             bt ^= 0b00000100;
         }
-        if self.tc == true {
+        if self.tc {
             bt ^= 0b00000010;
         }
-        if self.rd == true {
+        if self.rd {
             bt ^= 0b00000001;
         }
         buf.put_u8(bt);
@@ -138,7 +661,7 @@ impl<'a> DNSRequest<'a> {
         //  Rcode   - 4 bits
         bt = self.z;
         bt <<= 4;
-        if self.ra == true {
+        if self.ra {
             bt ^= 0b10000000;
         }
         bt ^= self.rcode & 0b00001111;
@@ -153,11 +676,11 @@ impl<'a> DNSRequest<'a> {
         // 16 bits (NSCOUNT)
         buf.put_u16_be(self.nscount);
 
-        // 16 bits (ARCOUNT)
-        buf.put_u16_be(self.arcount);
+        // 16 bits (ARCOUNT) - include the EDNS0 OPT record if requested
+        buf.put_u16_be(self.arcount + if self.edns_payload_size.is_some() { 1 } else { 0 });
 
         // Names
-        for name in &self.names {
+        for (name, qtype) in &self.questions {
             for part in name {
                 buf.put_u8(part.len() as u8);
                 for c in part.chars() {
@@ -166,28 +689,182 @@ impl<'a> DNSRequest<'a> {
             }
             buf.put_u8(0); // End of name
 
-            // QTYPE (Type A Query - host address)
-            buf.put_u8(0);
-            buf.put_u8(1);
+            // QTYPE (the record type the caller asked for)
+            buf.put_u16_be(qtype.to_num());
 
             // QCLASS (Class IN - internet address)
             buf.put_u8(0);
             buf.put_u8(1);
         }
 
+        // EDNS0 OPT pseudo-record in the additional section.
+        if let Some(payload_size) = self.edns_payload_size {
+            buf.put_u8(0); // NAME - root
+            buf.put_u16_be(41); // TYPE - OPT
+            buf.put_u16_be(payload_size); // CLASS - requestor's UDP payload size
+            buf.put_u32_be(0); // TTL - extended-rcode / version / flags
+            buf.put_u16_be(0); // RDLENGTH - no options
+        }
+
         buf
     }
 
-    fn add_question(&mut self, name: &'a String) {
-        // TODO: Validate name (only dots and numalpha?)
+    fn add_question(&mut self, name: &str, qtype: QueryType) -> Result<()> {
+        // Non-ASCII names are IDNA/punycode-encoded before the per-label
+        // checks so that the wire format only ever carries valid octets.
+        let ascii = domain_to_ascii(name)?;
 
-        let parts: Vec<_> = name.split('.').map(|x| x).collect();
-        self.names.push(parts);
+        let mut parts = Vec::new();
+        let mut encoded_len = 1; // the terminating root octet
+        for label in ascii.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(format!("label must be 1-63 bytes: {:?}", label).into());
+            }
+            // LDH plus underscore: `_` is required by RFC 2782 service labels
+            // (`_sip._tcp`) and common TXT names (`_dmarc`, `_acme-challenge`).
+            if !label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+            {
+                return Err(format!("label has invalid characters: {:?}", label).into());
+            }
+            // Each label costs its own length octet plus its bytes on the wire.
+            encoded_len += label.len() + 1;
+            parts.push(label.to_string());
+        }
+
+        // The 255-octet cap is on the wire-encoded name, not the dotted string.
+        if encoded_len > 255 {
+            return Err(format!("name exceeds 255 bytes when encoded: {}", name).into());
+        }
+
+        self.questions.push((parts, qtype));
+        Ok(())
     }
 
     fn qdcount(&self) -> u16 {
-        self.names.len() as u16
+        self.questions.len() as u16
+    }
+
+    // Advertise EDNS0 support with the given UDP payload size (e.g. 4096).
+    fn enable_edns(&mut self, payload_size: u16) {
+        self.edns_payload_size = Some(payload_size);
+    }
+}
+
+// Converts a domain name to its ASCII-compatible (IDNA) form: ASCII labels
+// are passed through lowercased, while labels with non-ASCII characters are
+// Punycode-encoded (RFC 3492) and given the `xn--` ACE prefix.
+fn domain_to_ascii(name: &str) -> Result<String> {
+    let mut out = Vec::new();
+    for label in name.split('.') {
+        if label.is_ascii() {
+            out.push(label.to_ascii_lowercase());
+        } else {
+            out.push(format!("xn--{}", punycode_encode(label)?));
+        }
+    }
+    Ok(out.join("."))
+}
+
+// Punycode encoder (RFC 3492). Returns the encoded basic-string for a single
+// label; the caller prepends the `xn--` prefix.
+fn punycode_encode(input: &str) -> Result<String> {
+    const BASE: u32 = 36;
+    const TMIN: u32 = 1;
+    const TMAX: u32 = 26;
+    const SKEW: u32 = 38;
+    const DAMP: u32 = 700;
+    const INITIAL_BIAS: u32 = 72;
+    const INITIAL_N: u32 = 128;
+
+    // RFC 3492 bias adaptation.
+    fn adapt(mut delta: u32, num_points: u32, first_time: bool) -> u32 {
+        delta /= if first_time { DAMP } else { 2 };
+        delta += delta / num_points;
+        let mut k = 0;
+        while delta > ((BASE - TMIN) * TMAX) / 2 {
+            delta /= BASE - TMIN;
+            k += BASE;
+        }
+        k + (((BASE - TMIN + 1) * delta) / (delta + SKEW))
+    }
+
+    // Maps a digit (0-35) to its basic code point: 0-25 -> a-z, 26-35 -> 0-9.
+    fn encode_digit(d: u32) -> char {
+        if d < 26 {
+            (b'a' + d as u8) as char
+        } else {
+            (b'0' + (d - 26) as u8) as char
+        }
+    }
+
+    let code_points: Vec<u32> = input.chars().map(|c| c as u32).collect();
+    let mut output = String::new();
+
+    // Copy the basic (ASCII) code points verbatim, then the delimiter.
+    let mut handled = 0u32;
+    for &c in &code_points {
+        if c < INITIAL_N {
+            output.push(c as u8 as char);
+            handled += 1;
+        }
+    }
+    let basic = handled;
+    if basic > 0 {
+        output.push('-');
     }
+
+    let mut n = INITIAL_N;
+    let mut delta = 0u32;
+    let mut bias = INITIAL_BIAS;
+    while (handled as usize) < code_points.len() {
+        let m = code_points
+            .iter()
+            .copied()
+            .filter(|&c| c >= n)
+            .min()
+            .ok_or("punycode: no code point to encode")?;
+
+        delta = delta
+            .checked_add((m - n) * (handled + 1))
+            .ok_or("punycode: overflow")?;
+        n = m;
+
+        for &c in &code_points {
+            if c < n {
+                delta = delta.checked_add(1).ok_or("punycode: overflow")?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        TMIN
+                    } else if k >= bias + TMAX {
+                        TMAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + ((q - t) % (BASE - t))));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+
+        delta += 1;
+        n += 1;
+    }
+
+    Ok(output)
 }
 
 fn get_bits(num: u16, count: usize) -> String {
@@ -208,66 +885,176 @@ fn get_bits(num: u16, count: usize) -> String {
 
 impl fmt::Debug for DNSResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "--- Begin of packet ---");
-        writeln!(f, "id:\t{}", self.id);
-        writeln!(f, "qr:\t{}", self.qr);
-        writeln!(f, "opcode:\t{}", get_bits(self.opcode as u16, 4));
-        writeln!(f, "aa:\t{}", self.aa);
-        writeln!(f, "tc:\t{}", self.tc);
-        writeln!(f, "rd:\t{}", self.rd);
-        writeln!(f, "ra:\t{}", self.ra);
-        writeln!(f, "z:\t{}", get_bits(self.z as u16, 3));
-        writeln!(f, "rcode:\t{}", get_bits(self.rcode as u16, 4));
-        writeln!(f, "--");
-        //writeln!(f, "qdcount:\t{}", get_bits(self.qdcount(), 16));
-        writeln!(f, "ancount:\t{}", get_bits(self.ancount, 16));
-        writeln!(f, "nscount:\t{}", get_bits(self.nscount, 16));
-        writeln!(f, "arcount:\t{}", get_bits(self.arcount, 16));
-        writeln!(f, "--");
-        let mut name_count = 1;
-        /*
-        for n in &self.names {
-            writeln!(f, "name #{}:", name_count);
-            name_count += 1;
-            for part in n {
-                writeln!(f, "(len: {})\t{}", part.len(), part);
-            }
+        writeln!(f, "--- Begin of packet ---")?;
+        writeln!(f, "id:\t{}", self.id)?;
+        writeln!(f, "qr:\t{}", self.qr)?;
+        writeln!(f, "opcode:\t{:?}", self.opcode)?;
+        writeln!(f, "aa:\t{}", self.aa)?;
+        writeln!(f, "tc:\t{}", self.tc)?;
+        writeln!(f, "rd:\t{}", self.rd)?;
+        writeln!(f, "ra:\t{}", self.ra)?;
+        writeln!(f, "z:\t{}", get_bits(self.z as u16, 3))?;
+        writeln!(f, "rcode:\t{:?}", self.rcode)?;
+        writeln!(f, "--")?;
+        writeln!(f, "qdcount:\t{}", get_bits(self.qdcount, 16))?;
+        writeln!(f, "ancount:\t{}", get_bits(self.ancount, 16))?;
+        writeln!(f, "nscount:\t{}", get_bits(self.nscount, 16))?;
+        writeln!(f, "arcount:\t{}", get_bits(self.arcount, 16))?;
+        writeln!(f, "--")?;
+        for rr in &self.answers {
+            writeln!(f, "answer:\t\t{:?}", rr)?;
+        }
+        for rr in &self.authorities {
+            writeln!(f, "authority:\t{:?}", rr)?;
+        }
+        for rr in &self.resources {
+            writeln!(f, "additional:\t{:?}", rr)?;
+        }
+        if let Some(payload_size) = self.edns_payload_size {
+            writeln!(f, "--")?;
+            writeln!(f, "edns udp size:\t{}", payload_size)?;
+            writeln!(f, "edns DO:\t{}", self.edns_do)?;
         }
-        */
         writeln!(f, "--- End of packet ---")
     }
 }
 
-impl<'a> fmt::Debug for DNSRequest<'a> {
+impl fmt::Debug for DNSRequest {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "--- Begin of packet ---");
-        writeln!(f, "id:\t{}", self.id);
-        writeln!(f, "qr:\t{}", self.qr);
-        writeln!(f, "opcode:\t{}", get_bits(self.opcode as u16, 4));
-        writeln!(f, "aa:\t{}", self.aa);
-        writeln!(f, "tc:\t{}", self.tc);
-        writeln!(f, "rd:\t{}", self.rd);
-        writeln!(f, "ra:\t{}", self.ra);
-        writeln!(f, "z:\t{}", get_bits(self.z as u16, 3));
-        writeln!(f, "rcode:\t{}", get_bits(self.rcode as u16, 4));
-        writeln!(f, "--");
-        writeln!(f, "qdcount:\t{}", get_bits(self.qdcount(), 16));
-        writeln!(f, "ancount:\t{}", get_bits(self.ancount, 16));
-        writeln!(f, "nscount:\t{}", get_bits(self.nscount, 16));
-        writeln!(f, "arcount:\t{}", get_bits(self.arcount, 16));
-        writeln!(f, "--");
-        let mut name_count = 1;
-        for n in &self.names {
-            writeln!(f, "name #{}:", name_count);
-            name_count += 1;
+        writeln!(f, "--- Begin of packet ---")?;
+        writeln!(f, "id:\t{}", self.id)?;
+        writeln!(f, "qr:\t{}", self.qr)?;
+        writeln!(f, "opcode:\t{}", get_bits(self.opcode as u16, 4))?;
+        writeln!(f, "aa:\t{}", self.aa)?;
+        writeln!(f, "tc:\t{}", self.tc)?;
+        writeln!(f, "rd:\t{}", self.rd)?;
+        writeln!(f, "ra:\t{}", self.ra)?;
+        writeln!(f, "z:\t{}", get_bits(self.z as u16, 3))?;
+        writeln!(f, "rcode:\t{}", get_bits(self.rcode as u16, 4))?;
+        writeln!(f, "--")?;
+        writeln!(f, "qdcount:\t{}", get_bits(self.qdcount(), 16))?;
+        writeln!(f, "ancount:\t{}", get_bits(self.ancount, 16))?;
+        writeln!(f, "nscount:\t{}", get_bits(self.nscount, 16))?;
+        writeln!(f, "arcount:\t{}", get_bits(self.arcount, 16))?;
+        writeln!(f, "--")?;
+        for (i, (n, qtype)) in self.questions.iter().enumerate() {
+            writeln!(f, "name #{} ({:?}):", i + 1, qtype)?;
             for part in n {
-                writeln!(f, "(len: {})\t{}", part.len(), part);
+                writeln!(f, "(len: {})\t{}", part.len(), part)?;
             }
         }
         writeln!(f, "--- End of packet ---")
     }
 }
 
+// Sends a query over UDP and parses the reply. If the server set the TC
+// (truncation) bit the datagram only held part of the answer, so the same
+// query is retried over TCP as required by the protocol.
+fn resolve(req: &DNSRequest, server: (&str, u16)) -> Result<DNSResponse> {
+    let query = req.to_buffer();
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    // The iterative resolver fires queries at arbitrary authoritative and
+    // root servers, any of which may be unreachable or silent; without a
+    // read timeout `recv_from` would block the whole walk forever.
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    socket.send_to(&query[..], server)?;
+
+    let mut buf = [0; 2048];
+    let (amt, _) = socket.recv_from(&mut buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+            format!("no response from {}:{} (timed out)", server.0, server.1)
+        }
+        _ => e.to_string(),
+    })?;
+
+    let mut packet = BytePacketBuffer::new(&buf[..amt]);
+    let mut resp = DNSResponse::new();
+    resp.from_buffer(&mut packet)?;
+
+    if resp.tc {
+        return resolve_tcp(&query, server);
+    }
+
+    Ok(resp)
+}
+
+// DNS-over-TCP frames every message with a 2-byte big-endian length prefix,
+// on both the query and the reply.
+fn resolve_tcp(query: &BytesMut, server: (&str, u16)) -> Result<DNSResponse> {
+    let mut stream = TcpStream::connect(server)?;
+
+    let len = query.len() as u16;
+    stream.write_all(&[(len >> 8) as u8, (len & 0xFF) as u8])?;
+    stream.write_all(&query[..])?;
+
+    let mut len_buf = [0u8; 2];
+    stream.read_exact(&mut len_buf)?;
+    let resp_len = ((len_buf[0] as usize) << 8) | (len_buf[1] as usize);
+
+    let mut resp_buf = vec![0u8; resp_len];
+    stream.read_exact(&mut resp_buf)?;
+
+    let mut packet = BytePacketBuffer::new(&resp_buf);
+    let mut resp = DNSResponse::new();
+    resp.from_buffer(&mut packet)?;
+
+    Ok(resp)
+}
+
+// Sends a single non-recursive (RD=0) query to a specific server. Used by
+// the iterative resolver to talk directly to the delegation chain.
+fn lookup(name: &str, qtype: QueryType, server: (&str, u16)) -> Result<DNSResponse> {
+    let mut req = DNSRequest::new();
+    req.rd = false;
+    req.add_question(name, qtype)?;
+    resolve(&req, server)
+}
+
+// Resolves a name by walking the delegation chain ourselves, starting at a
+// root server instead of asking a recursive resolver to do the work. Each
+// hop either returns an answer, an authoritative error, or a set of NS
+// referrals to follow.
+fn resolve_iterative(qname: &str, qtype: QueryType) -> Result<DNSResponse> {
+    // a.root-servers.net
+    let mut ns = String::from("198.41.0.4");
+    let max_hops = 16;
+
+    for _ in 0..max_hops {
+        let resp = lookup(qname, qtype, (ns.as_str(), 53))?;
+
+        // An answer, or an authoritative failure, ends the walk.
+        if !resp.answers.is_empty() {
+            return Ok(resp);
+        }
+        if resp.rcode == Rcode::NXDomain || resp.rcode == Rcode::ServFail {
+            return Ok(resp);
+        }
+
+        // Follow a delegation: prefer glue, otherwise resolve the NS name.
+        if let Some(addr) = resp.matching_glue() {
+            ns = addr;
+            continue;
+        }
+
+        let ns_host = match resp.unresolved_ns() {
+            Some(host) => host,
+            None => return Ok(resp),
+        };
+
+        let resolved = resolve_iterative(&ns_host, QueryType::A)?;
+        match resolved.first_a() {
+            Some(addr) => ns = addr,
+            // The NS name had no usable glue address (empty or CNAME-only
+            // result); that is a failure to follow the delegation, not an
+            // answer to the original query.
+            None => return Err(format!("could not resolve address for NS {}", ns_host).into()),
+        }
+    }
+
+    Err("hop limit exceeded".into())
+}
+
 fn dump_buffer(buf: &bytes::BytesMut) {
     println!("Binary packet representation:");
 
@@ -292,36 +1079,108 @@ fn main() {
 
     let name01 = String::from("www.wp.pl");
     let name02 = String::from("www.vatican.va");
-    req.add_question(&name01);
-    req.add_question(&name02);
+    req.add_question(&name01, QueryType::A)
+        .expect("Invalid name");
+    req.add_question(&name02, QueryType::A)
+        .expect("Invalid name");
+    req.enable_edns(4096);
     println!("{:?}", req);
 
     let binary_representation = req.to_buffer();
     dump_buffer(&binary_representation);
 
-    let socket = UdpSocket::bind("0.0.0.0:0").expect("Couldn't bind to address");
-    socket
-        .send_to(&binary_representation[..], ("8.8.8.8", 53))
-        .expect("Couldn't send DNS request");
+    let resp = resolve(&req, ("8.8.8.8", 53)).expect("Couldn't resolve query");
 
-    let mut buf = [0; 2048];
-    let (amt, _) = socket
-        .recv_from(&mut buf)
-        .expect("Couldn't receive response");
+    println!();
+    println!("{:?}", resp);
 
     println!();
-    println!("Received {} bytes of response\n", amt);
+    println!("Iterative resolution of {}:", name01);
+    let iterative =
+        resolve_iterative(&name01, QueryType::A).expect("Couldn't resolve iteratively");
+    println!("{:?}", iterative);
+}
 
-    let mut bb = BytesMut::with_capacity(amt);
-    for x in 0..amt {
-        bb.put_u8(buf[x]);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A hand-built response for `example.com` with two A answers. The first
+    // answer's NAME is a bare compression pointer back to the question
+    // (offset 12); the second is the label `www` followed by a pointer, so
+    // decoding both exercises `read_qname`'s pointer following.
+    fn canned_response() -> Vec<u8> {
+        let mut buf = vec![
+            0x12, 0x34, // id
+            0x81, 0x80, // qr=1, rd=1, ra=1, rcode=0
+            0x00, 0x01, // qdcount
+            0x00, 0x02, // ancount
+            0x00, 0x00, // nscount
+            0x00, 0x00, // arcount
+        ];
+        // Question: example.com IN A  (starts at offset 12).
+        buf.extend_from_slice(b"\x07example\x03com\x00");
+        buf.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]); // QTYPE A, QCLASS IN
+        // Answer 1: pointer to offset 12, A, TTL 256, 93.184.216.34.
+        buf.extend_from_slice(&[0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01]);
+        buf.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x00, 0x04]);
+        buf.extend_from_slice(&[93, 184, 216, 34]);
+        // Answer 2: "www" + pointer to offset 12, A, TTL 256, 1.2.3.4.
+        buf.extend_from_slice(&[0x03, b'w', b'w', b'w', 0xC0, 0x0C]);
+        buf.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        buf.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x00, 0x04]);
+        buf.extend_from_slice(&[1, 2, 3, 4]);
+        buf
     }
-    dump_buffer(&bb);
 
-    let mut xxx = buf.into_buf();
-    let mut resp = DNSResponse::new();
-    resp.from_buffer(&mut xxx);
+    #[test]
+    fn decodes_compressed_answers() {
+        let data = canned_response();
+        let mut packet = BytePacketBuffer::new(&data);
+        let mut resp = DNSResponse::new();
+        resp.from_buffer(&mut packet).unwrap();
 
-    println!();
-    println!("{:?}", resp);
+        assert_eq!(resp.ancount, 2);
+        assert_eq!(resp.answers.len(), 2);
+
+        match &resp.answers[0] {
+            ResourceRecord::A { domain, addr, ttl } => {
+                assert_eq!(domain, "example.com");
+                assert_eq!(*addr, Ipv4Addr::new(93, 184, 216, 34));
+                assert_eq!(*ttl, 256);
+            }
+            other => panic!("expected A record, got {:?}", other),
+        }
+
+        match &resp.answers[1] {
+            ResourceRecord::A { domain, addr, .. } => {
+                assert_eq!(domain, "www.example.com");
+                assert_eq!(*addr, Ipv4Addr::new(1, 2, 3, 4));
+            }
+            other => panic!("expected A record, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn punycode_encodes_idn_labels() {
+        // RFC-style ACE encoding: the non-ASCII label gets the xn-- prefix.
+        assert_eq!(
+            domain_to_ascii("bücher.example").unwrap(),
+            "xn--bcher-kva.example"
+        );
+        // Pure-ASCII names pass through lowercased and unprefixed.
+        assert_eq!(domain_to_ascii("WWW.Example.COM").unwrap(), "www.example.com");
+    }
+
+    #[test]
+    fn read_qname_caps_pointer_jumps() {
+        // A pointer at offset 12 that points to itself would loop forever
+        // without the jump cap.
+        let mut data = vec![0u8; 12];
+        data.extend_from_slice(&[0xC0, 0x0C]);
+        let mut packet = BytePacketBuffer::new(&data);
+        packet.seek(12);
+        let mut name = String::new();
+        assert!(packet.read_qname(&mut name).is_err());
+    }
 }